@@ -0,0 +1,165 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{env, fs};
+
+/// Per-class icon glyph, resolved through [`Config::icons`] with a fallback
+/// to [`Config::default_icon`] for anything not listed.
+const DEFAULT_ICONS: &[(&str, &str)] = &[
+    ("firefox", ""),
+    ("Alacritty", ""),
+    ("discord", ""),
+    ("Code", ""),
+];
+const DEFAULT_ICON: &str = "";
+
+const ONCLICK_TEMPLATE: &str = "i3-msg -t run_command workspace {num}";
+
+/// Digit style used for the instance-count badge appended after an app's icon.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconListFormat {
+    #[default]
+    Digits,
+    Superscript,
+    Subscript,
+}
+
+const DIGITS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+const SUPERSCRIPT: [&str; 10] = ["⁰", "¹", "²", "³", "⁴", "⁵", "⁶", "⁷", "⁸", "⁹"];
+const SUBSCRIPT: [&str; 10] = ["₀", "₁", "₂", "₃", "₄", "₅", "₆", "₇", "₈", "₉"];
+
+impl IconListFormat {
+    fn digit_glyphs(&self) -> &'static [&'static str; 10] {
+        match self {
+            IconListFormat::Digits => &DIGITS,
+            IconListFormat::Superscript => &SUPERSCRIPT,
+            IconListFormat::Subscript => &SUBSCRIPT,
+        }
+    }
+
+    pub fn format_count(&self, count: usize) -> String {
+        count
+            .to_string()
+            .chars()
+            .map(|c| self.digit_glyphs()[c.to_digit(10).unwrap() as usize])
+            .collect()
+    }
+}
+
+/// CSS class suffix (`i3wm-workspace-{suffix}`) used for each workspace
+/// visibility state.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct VisibilityClasses {
+    pub focused: String,
+    pub urgent: String,
+    pub visible: String,
+    pub hidden: String,
+}
+
+impl Default for VisibilityClasses {
+    fn default() -> Self {
+        Self {
+            focused: "focused".to_string(),
+            urgent: "urgent".to_string(),
+            visible: "visible".to_string(),
+            hidden: "hidden".to_string(),
+        }
+    }
+}
+
+impl VisibilityClasses {
+    pub fn class_for(&self, state: &str) -> &str {
+        match state {
+            "focused" => &self.focused,
+            "urgent" => &self.urgent,
+            "visible" => &self.visible,
+            _ => &self.hidden,
+        }
+    }
+}
+
+/// Everything that used to be hard-coded constants: the icon map, the box's
+/// `eww` attributes, the onclick command template, and whether workspaces
+/// should be renumbered to close gaps. Loaded once from a TOML file at
+/// startup and threaded through to `get_button`/`print_workspaces`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub icons: HashMap<String, String>,
+    pub default_icon: String,
+    pub icon_format: IconListFormat,
+    pub visibility_classes: VisibilityClasses,
+    pub box_orientation: String,
+    pub box_spacing: u32,
+    pub onclick_command: String,
+    pub renumber: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            icons: DEFAULT_ICONS
+                .iter()
+                .map(|(class, icon)| (class.to_string(), icon.to_string()))
+                .collect(),
+            default_icon: DEFAULT_ICON.to_string(),
+            icon_format: IconListFormat::default(),
+            visibility_classes: VisibilityClasses::default(),
+            box_orientation: "h".to_string(),
+            box_spacing: 5,
+            onclick_command: ONCLICK_TEMPLATE.to_string(),
+            renumber: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &PathBuf) -> Self {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Failed to parse config at {}: {e}", path.display());
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+
+    pub fn icon_for_class(&self, class: &str) -> &str {
+        self.icons
+            .get(class)
+            .map(String::as_str)
+            .unwrap_or(&self.default_icon)
+    }
+
+    pub fn onclick(&self, num: &usize) -> String {
+        self.onclick_command.replace("{num}", &num.to_string())
+    }
+}
+
+/// Command-line interface, replacing the old `env::args().nth(1)` lookup.
+#[derive(Parser)]
+#[command(author, version, about = "Render i3 workspaces as eww widgets")]
+pub struct Args {
+    /// Output name of the monitor to render workspaces for
+    pub monitor: String,
+
+    /// Path to the TOML config file
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn config_path(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(default_config_path)
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    let base = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(env::var("HOME").unwrap_or_else(|_| ".".to_string())).join(".config"));
+    base.join("i3-workspaces").join("config.toml")
+}
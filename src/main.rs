@@ -1,148 +1,241 @@
+mod config;
+
+use crate::config::{Args, Config};
+use clap::Parser;
 use i3_ipc::event::{Event, Subscribe, WorkspaceChange};
-use i3_ipc::reply::{Node, Workspace};
+use i3_ipc::reply::{Node, NodeType, Workspace};
 use i3_ipc::{Connect, I3Stream, I3};
-use indoc::{formatdoc, indoc};
+use indoc::formatdoc;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::borrow::{Borrow};
-use std::collections::{BTreeMap, HashMap};
-use std::{env, io, process};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
 use std::cell::RefCell;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
-const BOX: &str = indoc! {"
-    (box :class 'i3wm-workspaces'
-         :orientation 'h'
-         :spacing 5
-         :space-evenly 'false'
-"};
+/// Guards stdout so the signal handler's final empty payload can't interleave
+/// with an in-flight `print_workspaces` call from the event loop.
+static PRINTING: AtomicBool = AtomicBool::new(false);
 
-fn get_button(num: &usize, name: &str, vis: &str) -> String {
-    return formatdoc! {"
-        (button   :class 'i3wm-workspace-{vis}'
-                  :onclick 'i3-msg -t run_command workspace {num}'
-                  '{name}')",
-    num = num,
-    vis = vis,
-    name = name};
+/// Reconcile against a fresh `get_workspaces` call every this-many workspace
+/// events, to correct any drift the local deltas missed.
+const RECONCILE_INTERVAL: u32 = 50;
+
+/// Authoritative local record of a workspace on `monitor`, updated straight
+/// from `Event::Workspace` deltas instead of a round-trip per event.
+#[derive(Clone)]
+struct WorkspaceState {
+    name: String,
+    /// The workspace's name exactly as i3 reports it (`num;icon-suffix`),
+    /// kept alongside the display-only `name` so a rename can preserve the
+    /// suffix.
+    raw_name: String,
+    output: String,
+    focused: bool,
+    urgent: bool,
+    visible: bool,
+}
+
+impl WorkspaceState {
+    fn visibility(&self) -> &'static str {
+        if self.focused {
+            "focused"
+        } else if self.urgent {
+            "urgent"
+        } else if self.visible {
+            "visible"
+        } else {
+            "hidden"
+        }
+    }
+}
+
+fn get_button(num: &usize, name: &str, vis: &str, icons: &str, config: &Config) -> String {
+    let vis_class = config.visibility_classes.class_for(vis);
+    formatdoc! {"
+        (button   :class 'i3wm-workspace-{vis_class}'
+                  :onclick '{onclick}'
+                  '{name}{icons}')",
+    onclick = config.onclick(num),
+    vis_class = vis_class,
+    name = name,
+    icons = icons}
 }
 
 fn main() -> io::Result<()> {
-    let mut map: BTreeMap<usize, String> = BTreeMap::new();
+    let args = Args::parse();
+    let config = Config::load(&args.config_path());
+    let monitor = args.monitor;
+
+    spawn_shutdown_handler(config.clone());
+
+    let mut state: HashMap<usize, WorkspaceState> = HashMap::new();
+    let mut icons: HashMap<usize, String> = HashMap::new();
     let mut i3 = I3::connect()?;
-    let monitor = match env::args().nth(1) {
-        Some(m) => m,
-        None => {
-            println!("No monitor specified.");
-            process::exit(1)
-        }
-    };
 
-    print_initial(&mut i3, &mut map, &monitor);
+    seed_state(&mut i3, &mut state, &monitor);
+    refresh_icons(&mut i3, &state, &mut icons, &config);
+    print_workspaces(&render_buttons(&state, &icons, &config), &config);
 
-    let mut listener = I3Stream::conn_sub(&[Subscribe::Workspace]).unwrap();
+    let mut events_since_reconcile: u32 = 0;
+    let mut listener = I3Stream::conn_sub([Subscribe::Workspace, Subscribe::Window]).unwrap();
     for res in listener.listen() {
         let mut update = false;
         match res.unwrap() {
             Event::Workspace(e) => {
+                events_since_reconcile += 1;
                 match e.change {
                     WorkspaceChange::Urgent => {
                         let workspace = e.current.unwrap();
+                        let raw_name = workspace.name.clone().unwrap_or_default();
                         let (key, name) = get_name_key_from_node(&workspace).unwrap();
-                        map.insert(key, get_button(&key, &name, &"urgent".to_string()));
-                        update = true;
+                        let output = workspace.output.clone().unwrap_or_else(|| monitor.clone());
+                        if output == monitor {
+                            let entry = state.entry(key).or_insert_with(|| WorkspaceState {
+                                name: name.clone(),
+                                raw_name: raw_name.clone(),
+                                output,
+                                focused: false,
+                                urgent: false,
+                                visible: false,
+                            });
+                            entry.name = name;
+                            entry.raw_name = raw_name;
+                            entry.urgent = true;
+                            update = true;
+                        }
                     }
                     WorkspaceChange::Empty => {
                         // Workspace is dropped
                         let workspace = e.current.unwrap();
                         let key = workspace.name.unwrap().parse::<usize>().unwrap();
-                        map.remove(&key);
+                        state.remove(&key);
                         update = true;
+
+                        if config.renumber {
+                            renumber_workspaces(&mut i3, &mut state, &mut icons, &monitor);
+                        }
                     }
                     WorkspaceChange::Focus => {
                         // Focused a new workspace, may also call init or empty
-                        let mut workspace = e.old.unwrap();
-                        let (mut key, mut name) = get_name_key_from_node(&workspace).unwrap();
-
-                        if map.contains_key(&key) {
-                            match i3
-                                .get_workspaces()?
-                                .iter()
-                                .find(|w| &w.name == workspace.name.as_ref().unwrap())
-                            {
-                                Some(_) => {
-                                    map.insert(
-                                        key,
-                                        get_button(
-                                            &key,
-                                            &name,
-                                            &get_visibility_node(&mut i3, &workspace),
-                                        ),
-                                    );
-                                }
-                                None => {
-                                    map.remove(&key);
-                                }
-                            }
+                        let old_node = e.old.unwrap();
+                        let (old_key, _) = get_name_key_from_node(&old_node).unwrap();
+
+                        let new_node = e.current.unwrap();
+                        let (new_key, new_name) = get_name_key_from_node(&new_node).unwrap();
+                        let new_output = new_node.output.clone().unwrap_or_else(|| monitor.clone());
+
+                        if let Some(old_state) = state.get_mut(&old_key) {
+                            // A previously-focused workspace stays visible only
+                            // if the newly-focused one lives on another output;
+                            // on the same output it's been replaced on screen.
+                            let same_output = old_state.output == new_output;
+                            old_state.focused = false;
+                            old_state.visible = !same_output;
                             update = true;
                         }
 
-                        workspace = e.current.unwrap();
-                        (key, name) = get_name_key_from_node(&workspace).unwrap().clone();
-
-                        if map.contains_key(&key) {
-                            map.insert(key, get_button(&key, &name, &"focused".to_string()));
+                        if let Some(new_state) = state.get_mut(&new_key) {
+                            new_state.name = new_name;
+                            new_state.raw_name = new_node.name.clone().unwrap_or_default();
+                            new_state.output = new_output;
+                            new_state.focused = true;
+                            new_state.urgent = false;
+                            new_state.visible = true;
                             update = true;
                         }
                     }
                     WorkspaceChange::Init => {
                         // New workspace created
                         let workspace = e.current.unwrap();
+                        let raw_name = workspace.name.clone().unwrap_or_default();
                         let (key, name) = get_name_key_from_node(&workspace).unwrap();
-                        map.insert(
-                            key,
-                            get_button(&key, &name, &get_visibility_node(&mut i3, &workspace)),
-                        );
+                        let output = workspace.output.clone().unwrap_or_else(|| monitor.clone());
+                        if output == monitor {
+                            state.insert(
+                                key,
+                                WorkspaceState {
+                                    name,
+                                    raw_name,
+                                    output,
+                                    focused: false,
+                                    urgent: false,
+                                    visible: false,
+                                },
+                            );
+                        }
                     }
                     WorkspaceChange::Move => {
                         // Move output
                         let workspace = e.current.unwrap();
                         let output = &workspace.output;
+                        let raw_name = workspace.name.clone().unwrap_or_default();
                         let pair = get_name_key_from_node(&workspace).unwrap();
 
                         match output {
                             Some(ref o) => {
-                                if o == &monitor && !map.contains_key(&pair.0) {
-                                    map.insert(
+                                if o == &monitor && !state.contains_key(&pair.0) {
+                                    state.insert(
                                         pair.0,
-                                        get_button(
-                                            &pair.0,
-                                            &pair.1,
-                                            &get_visibility_node(&mut i3, &workspace),
-                                        ),
+                                        WorkspaceState {
+                                            name: pair.1,
+                                            raw_name,
+                                            output: o.clone(),
+                                            focused: false,
+                                            urgent: false,
+                                            visible: true,
+                                        },
                                     );
                                     update = true;
-                                } else if o != &monitor && map.contains_key(&pair.0) {
-                                    map.remove(&pair.0);
+                                } else if o != &monitor && state.contains_key(&pair.0) {
+                                    state.remove(&pair.0);
                                     update = true;
                                 }
                             }
                             _ => {
-                                update = map.remove(&pair.0).is_some();
+                                update = state.remove(&pair.0).is_some();
                             }
                         }
+
+                        if config.renumber {
+                            renumber_workspaces(&mut i3, &mut state, &mut icons, &monitor);
+                        }
                     }
                     _ => {}
                 }
+
+                if events_since_reconcile >= RECONCILE_INTERVAL {
+                    seed_state(&mut i3, &mut state, &monitor);
+                    refresh_icons(&mut i3, &state, &mut icons, &config);
+                    events_since_reconcile = 0;
+                    update = true;
+                }
+            }
+            Event::Window(_) => {
+                // A window opened, closed, or moved; workspace membership
+                // didn't change, only the icons drawn on top of it. This is
+                // the only event that needs a fresh layout tree.
+                refresh_icons(&mut i3, &state, &mut icons, &config);
+                update = true;
             }
             _ => unreachable!(),
         }
         if update {
-            print_workspaces(&map);
+            print_workspaces(&render_buttons(&state, &icons, &config), &config);
         }
     }
     Ok(())
 }
 
 thread_local! {
-    static NAME_KEY: RefCell<HashMap<usize, (usize, String)>> = RefCell::new(HashMap::new());
+    /// Keyed by i3 node id; caches `(raw_name, key, display_name)`. A rename
+    /// (e.g. from `renumber_workspaces`) keeps the node's id but changes its
+    /// reported name, so entries are invalidated by comparing the observed
+    /// raw name against the cached one rather than trusting the id alone.
+    static NAME_KEY: RefCell<HashMap<usize, (String, usize, String)>> = RefCell::new(HashMap::new());
 }
 
 fn get_name_key_from_workspace(workspace: &Workspace) -> Option<(usize, String)> {
@@ -150,33 +243,39 @@ fn get_name_key_from_workspace(workspace: &Workspace) -> Option<(usize, String)>
 }
 
 fn get_name_key_from_node(node: &Node) -> Option<(usize, String)> {
-    get_name_key(&node.id, &node.name.as_ref()?)
+    get_name_key(&node.id, node.name.as_ref()?)
 }
 
-fn get_name_key<'a>(id: &'a usize, name: &'a str) -> Option<(usize, String)> {
+fn get_name_key(id: &usize, name: &str) -> Option<(usize, String)> {
     NAME_KEY.with(|r| {
         let mut map = r.borrow_mut();
-        if !map.contains_key(id) {
-            map.insert(
-                *id,
-                name.split_once(";").map_or_else(
-                    || (name.parse::<usize>().unwrap(), name.to_string()),
-                    |(num, s_name)| {
-                        let mut name = s_name.to_string();
-                        name.retain(|c| !c.is_ascii());
-                        if name.len() == 0 {
-                            name.push('');
-                        }
-                        (num.parse().unwrap(), name)
-                    },
-                ),
+        let stale = map.get(id).is_none_or(|(raw_name, _, _)| raw_name != name);
+        if stale {
+            let (key, display_name) = name.split_once(';').map_or_else(
+                || (name.parse::<usize>().unwrap(), name.to_string()),
+                |(num, s_name)| {
+                    let mut display_name = s_name.to_string();
+                    display_name.retain(|c| !c.is_ascii());
+                    if display_name.is_empty() {
+                        display_name.push('•');
+                    }
+                    (num.parse().unwrap(), display_name)
+                },
             );
-        };
-        return map.get(id).cloned();
+            map.insert(*id, (name.to_string(), key, display_name));
+        }
+        map.get(id)
+            .map(|(_, key, display_name)| (*key, display_name.clone()))
     })
 }
 
-fn print_initial(i3: &mut I3Stream, map: &mut BTreeMap<usize, String>, monitor: &str) {
+/// Seeds (or reconciles) local state from a fresh `get_workspaces` call.
+/// Apart from `renumber_workspaces`, which asks i3 which numbers other
+/// outputs hold, this is the only place that round-trips to i3 for workspace
+/// state; the event loop otherwise mutates `state` purely from the incoming
+/// deltas.
+fn seed_state(i3: &mut I3Stream, state: &mut HashMap<usize, WorkspaceState>, monitor: &str) {
+    state.clear();
     for workspace in i3.get_workspaces().unwrap() {
         if workspace.output != monitor {
             continue;
@@ -187,47 +286,217 @@ fn print_initial(i3: &mut I3Stream, map: &mut BTreeMap<usize, String>, monitor:
             Some(w) => w,
         };
 
-        map.insert(key, get_button(&key, &name, &get_visibility_workspace(&workspace)));
+        state.insert(
+            key,
+            WorkspaceState {
+                name,
+                raw_name: workspace.name,
+                output: workspace.output,
+                focused: workspace.focused,
+                urgent: workspace.urgent,
+                visible: workspace.visible,
+            },
+        );
     }
-    print_workspaces(map);
 }
 
-fn get_visibility_node(i3: &mut I3Stream, node: &Node) -> String {
-    match i3
+/// Reassigns sequential positions 1..N to the surviving workspaces, renaming
+/// each one that moved via `i3-msg rename workspace <old> to <new>` and
+/// preserving its `;`-separated icon-name suffix. Opt-in, since it mutates
+/// i3 state rather than just how it's displayed.
+///
+/// Workspace numbers are global across all outputs, so positions already
+/// taken by another monitor's workspaces are skipped rather than reused.
+fn renumber_workspaces(
+    i3: &mut I3Stream,
+    state: &mut HashMap<usize, WorkspaceState>,
+    icons: &mut HashMap<usize, String>,
+    monitor: &str,
+) {
+    let occupied_elsewhere: HashSet<usize> = i3
         .get_workspaces()
-        .unwrap()
+        .map(|workspaces| {
+            workspaces
+                .into_iter()
+                .filter(|w| w.output != monitor)
+                .filter_map(|w| workspace_num(&w.name))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut sorted: Vec<usize> = state.keys().copied().collect();
+    sorted.sort();
+
+    let mut renumbered = HashMap::with_capacity(state.len());
+    let mut renumbered_icons = HashMap::with_capacity(icons.len());
+    let mut next_num = 1;
+    for old_num in sorted {
+        while occupied_elsewhere.contains(&next_num) {
+            next_num += 1;
+        }
+        let new_num = next_num;
+        next_num += 1;
+        let mut ws = state.remove(&old_num).unwrap();
+
+        if new_num != old_num {
+            let new_raw_name = match ws.raw_name.split_once(';') {
+                Some((_, suffix)) => format!("{};{}", new_num, suffix),
+                None => new_num.to_string(),
+            };
+
+            process::Command::new("i3-msg")
+                .args(["rename", "workspace", &ws.raw_name, "to", &new_raw_name])
+                .status()
+                .ok();
+
+            ws.raw_name = new_raw_name;
+        }
+
+        if let Some(icon) = icons.remove(&old_num) {
+            renumbered_icons.insert(new_num, icon);
+        }
+        renumbered.insert(new_num, ws);
+    }
+
+    *state = renumbered;
+    *icons = renumbered_icons;
+}
+
+/// Parses the leading `num` out of a raw i3 workspace name (`num` or
+/// `num;icon-suffix`).
+fn workspace_num(name: &str) -> Option<usize> {
+    name.split_once(';')
+        .map_or(name, |(num, _)| num)
+        .parse()
+        .ok()
+}
+
+/// Renders every cached workspace into a button, deriving the visibility
+/// class from the local flags and the icon string from `icons` rather than
+/// asking i3 for either.
+fn render_buttons(
+    state: &HashMap<usize, WorkspaceState>,
+    icons: &HashMap<usize, String>,
+    config: &Config,
+) -> BTreeMap<usize, String> {
+    state
         .iter()
-        .find(|w| w.id == node.id)
-    {
-        Some(w) => get_visibility_workspace(w),
-        None => "".to_string(),
+        .map(|(key, ws)| {
+            let icons = icons.get(key).map(String::as_str).unwrap_or("");
+            (*key, get_button(key, &ws.name, ws.visibility(), icons, config))
+        })
+        .collect()
+}
+
+/// Re-walks the layout tree and recomputes every tracked workspace's icon
+/// string in one pass. Only called at startup, on `Event::Window` and at the
+/// reconcile tick, since that's the only event that can change which windows
+/// are on screen; `Event::Workspace` deltas never re-walk the tree, and only
+/// `renumber_workspaces` moves existing `icons` entries to their new keys.
+fn refresh_icons(i3: &mut I3Stream, state: &HashMap<usize, WorkspaceState>, icons: &mut HashMap<usize, String>, config: &Config) {
+    let Ok(tree) = i3.get_tree() else { return };
+
+    for key in state.keys() {
+        icons.insert(*key, get_window_icons(&tree, *key, config));
+    }
+}
+
+/// Walks the i3 layout tree looking for the workspace numbered `ws_num`,
+/// then collects a glyph for every leaf window underneath it.
+fn get_window_icons(tree: &Node, ws_num: usize, config: &Config) -> String {
+    let mut classes = Vec::new();
+    if let Some(workspace_node) = find_workspace_node(tree, ws_num) {
+        collect_window_classes(workspace_node, &mut classes);
+    }
+
+    // Collapse duplicate classes into one glyph plus a count badge, while
+    // keeping each app in its first-seen order.
+    let mut order: Vec<String> = Vec::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for class in classes {
+        if !counts.contains_key(&class) {
+            order.push(class.clone());
+        }
+        *counts.entry(class).or_insert(0) += 1;
+    }
+
+    order
+        .iter()
+        .map(|class| {
+            let icon = config.icon_for_class(class);
+            let count = counts[class];
+            if count > 1 {
+                format!("{}{}", icon, config.icon_format.format_count(count))
+            } else {
+                icon.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+fn find_workspace_node(node: &Node, ws_num: usize) -> Option<&Node> {
+    if node.node_type == NodeType::Workspace && node.num == Some(ws_num as i32) {
+        return Some(node);
     }
+
+    node.nodes
+        .iter()
+        .chain(node.floating_nodes.iter())
+        .find_map(|child| find_workspace_node(child, ws_num))
 }
 
-fn get_visibility_workspace(workspace: &Workspace) -> String {
-    if workspace.focused {
-        "focused"
-    } else if workspace.urgent {
-        "urgent"
-    } else if workspace.visible {
-        "visible"
-    } else {
-        "hidden"
+fn collect_window_classes(node: &Node, classes: &mut Vec<String>) {
+    if node.window.is_some() {
+        if let Some(props) = &node.window_properties {
+            if let Some(class) = &props.class {
+                classes.push(class.clone());
+            }
+        }
+    }
+
+    for child in node.nodes.iter().chain(node.floating_nodes.iter()) {
+        collect_window_classes(child, classes);
     }
-    .to_string()
 }
 
-fn print_workspaces(map: &BTreeMap<usize, String>) {
+fn print_workspaces(map: &BTreeMap<usize, String>, config: &Config) {
+    while PRINTING
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        thread::yield_now();
+    }
+
     let mut string = formatdoc! {"
-    {box}
+    (box :class 'i3wm-workspaces'
+         :orientation '{orientation}'
+         :spacing {spacing}
+         :space-evenly 'false'
     {buttons})
     ",
-    box = BOX,
-    buttons = map.iter().map(|(_, v)| v.borrow()).collect::<Vec<_>>().join("") };
+    orientation = config.box_orientation,
+    spacing = config.box_spacing,
+    buttons = map.values().map(|v| v.borrow()).collect::<Vec<_>>().join("") };
     trim_newlines(&mut string);
     println!("{}", string);
+
+    PRINTING.store(false, Ordering::SeqCst);
 }
 
 fn trim_newlines(input: &mut String) {
     input.retain(|c| c != '\n');
 }
+
+/// Watches SIGINT/SIGTERM and emits one final empty widget payload so eww
+/// resets instead of freezing on the last-printed state when this process
+/// is killed mid-reload.
+fn spawn_shutdown_handler(config: Config) {
+    let mut signals = Signals::new([SIGINT, SIGTERM]).expect("failed to register signal handler");
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            print_workspaces(&BTreeMap::new(), &config);
+            process::exit(0);
+        }
+    });
+}